@@ -0,0 +1,197 @@
+use axum::extract::{FromRequestParts, Query, State};
+use axum::http::request::Parts;
+use axum::response::Json;
+use redis::Commands;
+use serde::{Deserialize, Serialize};
+
+use crate::error::AppError;
+use crate::protos::redis_demo::UserProfile;
+use crate::{read_profile, with_redis, SharedState, UserProfileJson};
+
+pub const USERS_INDEX_KEY: &str = "users:all_ids";
+
+/// Registers `user_id` in the `users:all_ids` index so it shows up in
+/// admin listing/deletion. Called from `create_profile` alongside the
+/// profile write.
+pub fn index_user(con: &mut impl Commands, user_id: &str) -> Result<(), AppError> {
+    let _: () = con.sadd(USERS_INDEX_KEY, user_id)?;
+    Ok(())
+}
+
+fn user_key(user_id: &str) -> String {
+    format!("user:{}:profile", user_id)
+}
+
+fn wallet_key(user_id: &str) -> String {
+    format!("user:{}:wallet", user_id)
+}
+
+/// One `SSCAN` step over the user index. Returns the cursor to pass back
+/// in for the next page, and the ids found this step ("0" means done).
+/// `count` is only a hint to Redis — a single step can return more or
+/// fewer ids than that, so callers must still cap the result themselves.
+fn scan_user_ids_page(
+    con: &mut impl Commands,
+    cursor: &str,
+    count: usize,
+) -> Result<(String, Vec<String>), AppError> {
+    let (next_cursor, ids): (String, Vec<String>) = redis::cmd("SSCAN")
+        .arg(USERS_INDEX_KEY)
+        .arg(cursor)
+        .arg("COUNT")
+        .arg(count)
+        .query(con)?;
+    Ok((next_cursor, ids))
+}
+
+/// Deletes a user's profile, wallet, play history, and index membership
+/// in one pipeline so a caller never observes a half-deleted account.
+fn delete_user(con: &mut impl Commands, user_id: &str) -> Result<(), AppError> {
+    let profile: UserProfile = read_profile(con, user_id)?;
+    redis::pipe()
+        .atomic()
+        .del(user_key(user_id))
+        .ignore()
+        .del(wallet_key(user_id))
+        .ignore()
+        .del(&profile.history_key)
+        .ignore()
+        .srem(USERS_INDEX_KEY, user_id)
+        .ignore()
+        .query::<()>(con)?;
+    Ok(())
+}
+
+fn generate_invitation_token() -> String {
+    let bytes: [u8; 16] = rand::random();
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn invitation_key(token: &str) -> String {
+    format!("invitation:{}", token)
+}
+
+const DEFAULT_INVITATION_TTL_SECS: u64 = 60 * 60 * 24 * 7;
+
+fn create_invitation(con: &mut impl Commands, ttl_secs: u64) -> Result<String, AppError> {
+    let token = generate_invitation_token();
+    let _: () = con.set_ex(invitation_key(&token), "pending", ttl_secs)?;
+    Ok(token)
+}
+
+/// Redeems an invitation `token`, a one-time use enforced by fetching and
+/// deleting it in a single round trip: a second redemption finds nothing
+/// left to delete and is rejected.
+pub fn consume_invitation(con: &mut impl Commands, token: &str) -> Result<(), AppError> {
+    let existed: Option<String> = redis::cmd("GETDEL")
+        .arg(invitation_key(token))
+        .query(con)?;
+    existed
+        .map(|_| ())
+        .ok_or_else(|| AppError::Forbidden("invalid or expired invitation token".to_string()))
+}
+
+/// Extractor that gates admin-only routes behind a shared secret header
+/// (`X-Admin-Token`). A placeholder until admin access rides on the JWT
+/// auth layer.
+pub struct AdminGuard;
+
+impl<S> FromRequestParts<S> for AdminGuard
+where
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let expected = std::env::var("ADMIN_TOKEN").unwrap_or_default();
+        let provided = parts
+            .headers
+            .get("x-admin-token")
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default();
+
+        if expected.is_empty() || provided != expected {
+            return Err(AppError::Forbidden("admin token required".to_string()));
+        }
+        Ok(AdminGuard)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct UsersPageQuery {
+    cursor: Option<String>,
+    limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub struct UsersPage {
+    users: Vec<UserProfileJson>,
+    cursor: String,
+}
+
+pub async fn list_users_handler(
+    _admin: AdminGuard,
+    State(state): State<SharedState>,
+    Query(query): Query<UsersPageQuery>,
+) -> Result<Json<UsersPage>, AppError> {
+    let cursor = query.cursor.unwrap_or_else(|| "0".to_string());
+    let limit = query.limit.unwrap_or(20);
+
+    let (next_cursor, profiles) = with_redis(&state, |con| {
+        let (next_cursor, ids) = scan_user_ids_page(con, &cursor, limit)?;
+        // `COUNT` above is only a hint, so SSCAN can hand back more ids
+        // than `limit` in a single step; cap the page ourselves.
+        let mut profiles = Vec::with_capacity(ids.len().min(limit));
+        for id in ids.iter().take(limit) {
+            if let Ok(profile) = read_profile(con, id) {
+                profiles.push(profile);
+            }
+        }
+        Ok((next_cursor, profiles))
+    })
+    .await?;
+
+    let users = profiles
+        .into_iter()
+        .map(|p| UserProfileJson {
+            id: p.id,
+            username: p.username,
+            email: p.email,
+            subscription_level: p.subscription_level,
+        })
+        .collect();
+
+    Ok(Json(UsersPage {
+        users,
+        cursor: next_cursor,
+    }))
+}
+
+pub async fn delete_user_handler(
+    _admin: AdminGuard,
+    State(state): State<SharedState>,
+    axum::extract::Path(user_id): axum::extract::Path<String>,
+) -> Result<(), AppError> {
+    with_redis(&state, |con| delete_user(con, &user_id)).await
+}
+
+#[derive(Deserialize)]
+pub struct CreateInvitationRequest {
+    ttl_secs: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct InvitationJson {
+    token: String,
+    ttl_secs: u64,
+}
+
+pub async fn create_invitation_handler(
+    _admin: AdminGuard,
+    State(state): State<SharedState>,
+    Json(payload): Json<CreateInvitationRequest>,
+) -> Result<Json<InvitationJson>, AppError> {
+    let ttl_secs = payload.ttl_secs.unwrap_or(DEFAULT_INVITATION_TTL_SECS);
+    let token = with_redis(&state, |con| create_invitation(con, ttl_secs)).await?;
+    Ok(Json(InvitationJson { token, ttl_secs }))
+}
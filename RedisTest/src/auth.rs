@@ -0,0 +1,125 @@
+use std::env;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use axum::extract::{FromRequestParts, State};
+use axum::http::request::Parts;
+use axum::response::Json;
+use axum_extra::extract::cookie::Cookie;
+use axum_extra::extract::CookieJar;
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+
+use crate::error::AppError;
+use crate::{read_profile, with_redis, SharedState};
+
+pub const AUTH_COOKIE_NAME: &str = "session";
+
+/// Signing secret and token lifetime for issued sessions.
+pub struct AuthConfig {
+    pub secret: String,
+    pub token_lifetime_secs: u64,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        AuthConfig {
+            secret: env::var("JWT_SECRET").unwrap_or_else(|_| "dev-secret-change-me".to_string()),
+            token_lifetime_secs: env::var("JWT_LIFETIME_SECS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(60 * 60 * 24),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Claims {
+    sub: String,
+    exp: usize,
+}
+
+fn issue_token(config: &AuthConfig, user_id: &str) -> Result<String, AppError> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| AppError::Internal(e.to_string()))?
+        .as_secs();
+    let claims = Claims {
+        sub: user_id.to_string(),
+        exp: (now + config.token_lifetime_secs) as usize,
+    };
+    encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(config.secret.as_bytes()),
+    )
+    .map_err(|e| AppError::Internal(e.to_string()))
+}
+
+/// The authenticated caller, recovered from the session cookie. Handlers
+/// that accept this extractor reject the request outright if the cookie
+/// is missing or the token doesn't validate.
+pub struct AuthUser {
+    pub id: String,
+}
+
+impl FromRequestParts<SharedState> for AuthUser {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &SharedState,
+    ) -> Result<Self, Self::Rejection> {
+        let jar = CookieJar::from_headers(&parts.headers);
+        let token = jar
+            .get(AUTH_COOKIE_NAME)
+            .map(|c| c.value().to_string())
+            .ok_or(AppError::Unauthorized)?;
+
+        let data = decode::<Claims>(
+            &token,
+            &DecodingKey::from_secret(state.auth.secret.as_bytes()),
+            &Validation::default(),
+        )
+        .map_err(|_| AppError::Unauthorized)?;
+
+        Ok(AuthUser {
+            id: data.claims.sub,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+pub struct LoginRequest {
+    user_id: String,
+}
+
+#[derive(Serialize)]
+pub struct LoginResponse {
+    user_id: String,
+}
+
+pub async fn login_handler(
+    State(state): State<SharedState>,
+    Json(payload): Json<LoginRequest>,
+) -> Result<(CookieJar, Json<LoginResponse>), AppError> {
+    // TODO: demo-only, no credential verification — this issues a valid
+    // session for any `user_id` that exists, with no password/secret
+    // check, so it doesn't actually stop one account from impersonating
+    // another. Fine for exercising the cookie/JWT plumbing, not for a
+    // real login flow.
+    // Confirm the account exists before handing out a session for it.
+    with_redis(&state, |con| read_profile(con, &payload.user_id)).await?;
+
+    let token = issue_token(&state.auth, &payload.user_id)?;
+    let mut cookie = Cookie::new(AUTH_COOKIE_NAME, token);
+    cookie.set_http_only(true);
+    cookie.set_path("/");
+
+    let jar = CookieJar::new().add(cookie);
+    Ok((
+        jar,
+        Json(LoginResponse {
+            user_id: payload.user_id,
+        }),
+    ))
+}
@@ -0,0 +1,90 @@
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::Serialize;
+
+/// Crate-wide error type. Every fallible CRUD/transfer function returns
+/// this instead of `Box<dyn Error>` so handlers don't have to recover
+/// intent by string-matching and axum can map each variant to the right
+/// HTTP status directly.
+#[derive(Debug, thiserror::Error)]
+pub enum AppError {
+    #[error("not found")]
+    NotFound,
+    #[error("insufficient funds: have {have}, need {need}")]
+    InsufficientFunds { have: i32, need: i32 },
+    #[error("invalid input: {0}")]
+    InvalidInput(String),
+    #[error("forbidden: {0}")]
+    Forbidden(String),
+    #[error("unauthorized")]
+    Unauthorized,
+    #[error("failed to decode stored data: {0}")]
+    Decode(#[from] prost::DecodeError),
+    #[error("redis error: {0}")]
+    Redis(#[from] redis::RedisError),
+    #[error("redis pool error: {0}")]
+    Pool(#[from] r2d2::Error),
+    #[error("internal error: {0}")]
+    Internal(String),
+}
+
+impl AppError {
+    fn status(&self) -> StatusCode {
+        match self {
+            AppError::NotFound => StatusCode::NOT_FOUND,
+            AppError::InsufficientFunds { .. } => StatusCode::BAD_REQUEST,
+            AppError::InvalidInput(_) => StatusCode::BAD_REQUEST,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::Unauthorized => StatusCode::UNAUTHORIZED,
+            AppError::Decode(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::Redis(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::Pool(_) => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            AppError::NotFound => "NOT_FOUND",
+            AppError::InsufficientFunds { .. } => "INSUFFICIENT_FUNDS",
+            AppError::InvalidInput(_) => "INVALID_INPUT",
+            AppError::Forbidden(_) => "FORBIDDEN",
+            AppError::Unauthorized => "UNAUTHORIZED",
+            AppError::Decode(_) => "DECODE_ERROR",
+            AppError::Redis(_) => "REDIS_ERROR",
+            AppError::Pool(_) => "POOL_ERROR",
+            AppError::Internal(_) => "INTERNAL_ERROR",
+        }
+    }
+
+    /// Whether retrying the operation that produced this error might
+    /// succeed. Only connectivity failures are transient; anything the
+    /// caller did wrong (bad input, insufficient funds) never is.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            AppError::Redis(e) => {
+                e.is_connection_dropped() || e.is_connection_refusal() || e.is_timeout()
+            }
+            AppError::Pool(_) => true,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: String,
+    code: &'static str,
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        let body = ErrorBody {
+            error: self.to_string(),
+            code: self.code(),
+        };
+        (status, Json(body)).into_response()
+    }
+}
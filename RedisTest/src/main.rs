@@ -1,6 +1,6 @@
 use axum::{
     extract::{Path, State},
-    http::{StatusCode, HeaderValue},
+    http::{header, HeaderName, HeaderValue, Method},
     response::Json,
     routing::{get, post},
     Router,
@@ -8,15 +8,62 @@ use axum::{
 use prost::Message;
 use redis::Commands;
 use serde::{Deserialize, Serialize};
+use std::env;
 use std::error::Error;
-use std::io::{Error as IoError, ErrorKind};
 use std::sync::{Arc, Mutex};
-use tower_http::cors::{CorsLayer, Any};
+use tower_http::cors::CorsLayer;
 
+mod admin;
+mod auth;
+mod error;
+mod plays;
 mod protos;
+mod rate;
+mod retry;
+use auth::{AuthConfig, AuthUser};
+use error::AppError;
 use protos::redis_demo::{CreditWallet, Genre, SubscriptionLevel, UserProfile};
+use rate::{FixedRate, LatestRate, LiveRate, Rate, RateSource};
+use retry::RetryConfig;
 
-type SharedState = Arc<Mutex<redis::Connection>>;
+type RedisPool = r2d2::Pool<redis::Client>;
+
+struct AppState {
+    redis: RedisPool,
+    rate: RateSource,
+    retry: RetryConfig,
+    auth: AuthConfig,
+}
+
+/// Checks out a pooled connection and runs `op` against it, retrying
+/// transient errors per `state.retry`. Acquiring the connection is part
+/// of the retried unit of work, so a pool exhausted by a dropped
+/// connection gets a chance to recover before the caller sees an error.
+pub(crate) async fn with_redis<T>(
+    state: &AppState,
+    mut op: impl FnMut(&mut redis::Connection) -> Result<T, AppError>,
+) -> Result<T, AppError> {
+    retry::retryable(&state.retry, || {
+        let mut con = state.redis.get()?;
+        op(&mut con)
+    })
+    .await
+}
+
+/// Checks out a pooled connection and runs `op` against it once, with no
+/// retry. Use this instead of [`with_redis`] when `op` contains writes
+/// that aren't idempotent (e.g. `LPUSH`, `ZINCRBY`): retrying those on a
+/// transient error would replay the writes and double them up, rather
+/// than just redoing safe, read-only work.
+pub(crate) fn with_redis_once<T>(
+    state: &AppState,
+    mut op: impl FnMut(&mut redis::Connection) -> Result<T, AppError>,
+) -> Result<T, AppError> {
+    let mut con = state.redis.get()?;
+    op(&mut con)
+}
+
+pub(crate) type SharedState = Arc<AppState>;
 
 #[derive(Serialize, Deserialize)]
 struct GenreJson {
@@ -26,11 +73,23 @@ struct GenreJson {
 }
 
 #[derive(Serialize, Deserialize)]
-struct UserProfileJson {
+pub(crate) struct UserProfileJson {
+    pub(crate) id: String,
+    pub(crate) username: String,
+    pub(crate) email: String,
+    pub(crate) subscription_level: i32,
+}
+
+/// Signup payload. Distinct from `UserProfileJson` because creation also
+/// requires redeeming an invitation token, which existing profiles don't
+/// carry.
+#[derive(Deserialize)]
+struct CreateUserProfileRequest {
     id: String,
     username: String,
     email: String,
     subscription_level: i32,
+    invitation_token: String,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -44,32 +103,35 @@ struct TransferRequest {
     amount: i32,
 }
 
-fn decode_protobuf<T: Message + Default>(bytes: Vec<u8>) -> Result<T, Box<dyn Error>> {
-    T::decode(&bytes[..]).map_err(|e| {
-        Box::new(IoError::new(
-            ErrorKind::InvalidData,
-            format!("Protobuf Decode failed: {}", e),
-        )) as Box<dyn Error>
-    })
+#[derive(Serialize)]
+struct RateJson {
+    ask: f64,
 }
 
-fn create_genre(con: &mut impl Commands, genre: Genre) -> Result<Genre, Box<dyn Error>> {
+pub(crate) fn decode_protobuf<T: Message + Default>(bytes: Vec<u8>) -> Result<T, AppError> {
+    Ok(T::decode(&bytes[..])?)
+}
+
+fn create_genre(con: &mut impl Commands, genre: Genre) -> Result<Genre, AppError> {
     let key = format!("genre:{}:metadata", genre.id);
     let index_key = "genres:all_ids";
     let mut buf = Vec::new();
-    genre.encode(&mut buf)?;
+    genre.encode(&mut buf).map_err(|e| AppError::InvalidInput(e.to_string()))?;
     let _: () = con.set(&key, buf)?;
     let _: () = con.sadd(index_key, &genre.id)?;
     read_genre(con, &genre.id)
 }
 
-fn read_genre(con: &mut impl Commands, genre_id: &str) -> Result<Genre, Box<dyn Error>> {
+pub(crate) fn read_genre(con: &mut impl Commands, genre_id: &str) -> Result<Genre, AppError> {
     let key = format!("genre:{}:metadata", genre_id);
     let bytes: Vec<u8> = con.get(&key)?;
+    if bytes.is_empty() {
+        return Err(AppError::NotFound);
+    }
     decode_protobuf(bytes)
 }
 
-fn read_all_genres(con: &mut impl Commands) -> Result<Vec<Genre>, Box<dyn Error>> {
+fn read_all_genres(con: &mut impl Commands) -> Result<Vec<Genre>, AppError> {
     let index_key = "genres:all_ids";
     let genre_ids: Vec<String> = con.smembers(index_key)?;
     let mut genres = Vec::with_capacity(genre_ids.len());
@@ -81,35 +143,42 @@ fn read_all_genres(con: &mut impl Commands) -> Result<Vec<Genre>, Box<dyn Error>
     Ok(genres)
 }
 
-fn create_profile(con: &mut impl Commands, profile: UserProfile) -> Result<UserProfile, Box<dyn Error>> {
+fn create_profile(con: &mut impl Commands, profile: UserProfile) -> Result<UserProfile, AppError> {
     let key = format!("user:{}:profile", profile.id);
     let mut buf = Vec::new();
-    profile.encode(&mut buf)?;
+    profile.encode(&mut buf).map_err(|e| AppError::InvalidInput(e.to_string()))?;
     let _: () = con.set(&key, buf)?;
+    admin::index_user(con, &profile.id)?;
     read_profile(con, &profile.id)
 }
 
-fn read_profile(con: &mut impl Commands, user_id: &str) -> Result<UserProfile, Box<dyn Error>> {
+pub(crate) fn read_profile(con: &mut impl Commands, user_id: &str) -> Result<UserProfile, AppError> {
     let key = format!("user:{}:profile", user_id);
     let bytes: Vec<u8> = con.get(&key)?;
+    if bytes.is_empty() {
+        return Err(AppError::NotFound);
+    }
     decode_protobuf(bytes)
 }
 
-fn update_profile(con: &mut impl Commands, profile: UserProfile) -> Result<UserProfile, Box<dyn Error>> {
+fn update_profile(con: &mut impl Commands, profile: UserProfile) -> Result<UserProfile, AppError> {
     let key = format!("user:{}:profile", profile.id);
     let mut buf = Vec::new();
-    profile.encode(&mut buf)?;
+    profile.encode(&mut buf).map_err(|e| AppError::InvalidInput(e.to_string()))?;
     let _: () = con.set(&key, buf)?;
     Ok(profile)
 }
 
-fn read_wallet(con: &mut impl Commands, user_id: &str) -> Result<CreditWallet, Box<dyn Error>> {
+fn read_wallet(con: &mut impl Commands, user_id: &str) -> Result<CreditWallet, AppError> {
     let key = format!("user:{}:wallet", user_id);
     let bytes: Vec<u8> = con.get(&key)?;
+    if bytes.is_empty() {
+        return Err(AppError::NotFound);
+    }
     decode_protobuf(bytes)
 }
 
-fn create_wallet(con: &mut impl Commands, user_id: &str) -> Result<CreditWallet, Box<dyn Error>> {
+fn create_wallet(con: &mut impl Commands, user_id: &str) -> Result<CreditWallet, AppError> {
     let key = format!("user:{}:wallet", user_id);
     // Initial balance: 100 coins and 0 credits for new users
     let wallet = CreditWallet {
@@ -117,7 +186,7 @@ fn create_wallet(con: &mut impl Commands, user_id: &str) -> Result<CreditWallet,
         credit_balance: 0,
     };
     let mut buf = Vec::new();
-    wallet.encode(&mut buf)?;
+    wallet.encode(&mut buf).map_err(|e| AppError::InvalidInput(e.to_string()))?;
     let _: () = con.set(&key, buf)?;
     Ok(wallet)
 }
@@ -126,102 +195,113 @@ fn transfer_credit_transaction(
     con: &mut impl Commands,
     user_id: &str,
     transfer_amount: i32,
-) -> Result<CreditWallet, Box<dyn Error>> {
+    rate: Rate,
+) -> Result<CreditWallet, AppError> {
     if transfer_amount <= 0 {
-        return Err(Box::new(IoError::new(
-            ErrorKind::InvalidInput,
-            "Transfer amount must be positive",
-        )));
+        return Err(AppError::InvalidInput(
+            "Transfer amount must be positive".to_string(),
+        ));
     }
 
     let balance_key = format!("user:{}:wallet", user_id);
-    let final_wallet: CreditWallet = redis::transaction(con, &[&balance_key], |con, pipe| {
-        let bytes: Vec<u8> = con.get(&balance_key)?;
-        let mut current: CreditWallet = match decode_protobuf(bytes) {
-            Ok(b) => b,
-            Err(e) => {
-                return Err(IoError::new(
-                    ErrorKind::InvalidData,
-                    format!("Decode error in transaction: {}", e),
-                ).into());
+    // `redis::transaction`'s closure can only abort with a `RedisError`, so
+    // business errors are stashed here and recovered once the transaction
+    // returns, instead of being string-matched out of the wire error.
+    let mut failure: Option<AppError> = None;
+
+    let transaction_result: redis::RedisResult<CreditWallet> =
+        redis::transaction(con, &[&balance_key], |con, pipe| {
+            let bytes: Vec<u8> = con.get(&balance_key)?;
+            let mut current: CreditWallet = match decode_protobuf(bytes) {
+                Ok(c) => c,
+                Err(e) => {
+                    failure = Some(e);
+                    return Err((redis::ErrorKind::ExtensionError, "aborted").into());
+                }
+            };
+
+            if current.coin_balance < transfer_amount {
+                failure = Some(AppError::InsufficientFunds {
+                    have: current.coin_balance,
+                    need: transfer_amount,
+                });
+                return Err((redis::ErrorKind::ExtensionError, "aborted").into());
             }
-        };
-
-        if current.coin_balance < transfer_amount {
-            return Err(IoError::new(
-                ErrorKind::InvalidInput,
-                format!("Insufficient coins. Current balance: {}, requested: {}",
-                        current.coin_balance, transfer_amount),
-            ).into());
-        }
 
-        current.coin_balance -= transfer_amount;
-        current.credit_balance += transfer_amount;
-        let mut new_buf = Vec::new();
-        if current.encode(&mut new_buf).is_err() {
-            return Err(IoError::new(
-                ErrorKind::InvalidData,
-                "Protobuf Encode failed in transaction",
-            ).into());
-        }
-        pipe.set(&balance_key, new_buf).ignore().query::<()>(con)?;
-        Ok(Some(current))
-    })?;
-    Ok(final_wallet)
+            let credits_gained = (transfer_amount as f64 * rate.ask).floor() as i32;
+            current.coin_balance -= transfer_amount;
+            current.credit_balance += credits_gained;
+            let mut new_buf = Vec::new();
+            if current.encode(&mut new_buf).is_err() {
+                failure = Some(AppError::InvalidInput(
+                    "failed to encode wallet".to_string(),
+                ));
+                return Err((redis::ErrorKind::ExtensionError, "aborted").into());
+            }
+            pipe.set(&balance_key, new_buf).ignore().query::<()>(con)?;
+            Ok(Some(current))
+        });
+
+    match (transaction_result, failure) {
+        (Ok(wallet), _) => Ok(wallet),
+        (Err(_), Some(app_err)) => Err(app_err),
+        (Err(e), None) => Err(AppError::Redis(e)),
+    }
 }
 
-async fn get_all_genres(State(state): State<SharedState>) -> Result<Json<Vec<GenreJson>>, StatusCode> {
-    let mut con = state.lock().unwrap();
-    match read_all_genres(&mut *con) {
-        Ok(genres) => {
-            let json_genres: Vec<GenreJson> = genres.into_iter().map(|g| GenreJson {
-                id: g.id,
-                name: g.name,
-                listeners: g.listeners,
-            }).collect();
-            Ok(Json(json_genres))
-        }
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
-    }
+async fn get_all_genres(State(state): State<SharedState>) -> Result<Json<Vec<GenreJson>>, AppError> {
+    let genres = with_redis(&state, |con| read_all_genres(con)).await?;
+    let json_genres: Vec<GenreJson> = genres.into_iter().map(|g| GenreJson {
+        id: g.id,
+        name: g.name,
+        listeners: g.listeners,
+    }).collect();
+    Ok(Json(json_genres))
 }
 
 async fn create_genre_handler(
     State(state): State<SharedState>,
     Json(payload): Json<GenreJson>,
-) -> Result<Json<GenreJson>, StatusCode> {
-    let mut con = state.lock().unwrap();
+) -> Result<Json<GenreJson>, AppError> {
     let genre = Genre {
         id: payload.id,
         name: payload.name,
         listeners: payload.listeners,
     };
-    match create_genre(&mut *con, genre) {
-        Ok(g) => Ok(Json(GenreJson { id: g.id, name: g.name, listeners: g.listeners })),
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    let g = with_redis(&state, |con| create_genre(con, genre.clone())).await?;
+    Ok(Json(GenreJson { id: g.id, name: g.name, listeners: g.listeners }))
+}
+
+/// Rejects the request unless the authenticated caller is the user the
+/// path refers to.
+pub(crate) fn require_self(auth: &AuthUser, user_id: &str) -> Result<(), AppError> {
+    if auth.id != user_id {
+        return Err(AppError::Forbidden(
+            "cannot act on another user's account".to_string(),
+        ));
     }
+    Ok(())
 }
 
 async fn get_user_profile(
+    auth: AuthUser,
     State(state): State<SharedState>,
     Path(user_id): Path<String>,
-) -> Result<Json<UserProfileJson>, StatusCode> {
-    let mut con = state.lock().unwrap();
-    match read_profile(&mut *con, &user_id) {
-        Ok(profile) => Ok(Json(UserProfileJson {
-            id: profile.id,
-            username: profile.username,
-            email: profile.email,
-            subscription_level: profile.subscription_level,
-        })),
-        Err(_) => Err(StatusCode::NOT_FOUND),
-    }
+) -> Result<Json<UserProfileJson>, AppError> {
+    require_self(&auth, &user_id)?;
+    let profile = with_redis(&state, |con| read_profile(con, &user_id)).await?;
+    Ok(Json(UserProfileJson {
+        id: profile.id,
+        username: profile.username,
+        email: profile.email,
+        subscription_level: profile.subscription_level,
+    }))
 }
 
 async fn create_user_profile(
     State(state): State<SharedState>,
-    Json(payload): Json<UserProfileJson>,
-) -> Result<Json<UserProfileJson>, StatusCode> {
-    let mut con = state.lock().unwrap();
+    Json(payload): Json<CreateUserProfileRequest>,
+) -> Result<Json<UserProfileJson>, AppError> {
     let profile = UserProfile {
         id: payload.id.clone(),
         username: payload.username,
@@ -230,30 +310,31 @@ async fn create_user_profile(
         history_key: format!("{}:history", payload.id),
     };
 
-    match create_profile(&mut *con, profile) {
-        Ok(p) => {
-            // Create initial wallet with starting balance
-            if let Err(_) = create_wallet(&mut *con, &p.id) {
-                return Err(StatusCode::INTERNAL_SERVER_ERROR);
-            }
-
-            Ok(Json(UserProfileJson {
-                id: p.id,
-                username: p.username,
-                email: p.email,
-                subscription_level: p.subscription_level,
-            }))
-        }
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
-    }
+    // `consume_invitation` is a non-idempotent GETDEL, so this runs once
+    // with no retry: replaying it after a transient error would find the
+    // token already deleted and reject a signup that should have
+    // succeeded, the same hazard `with_redis_once` exists for in plays.rs.
+    let p = with_redis_once(&state, |con| {
+        admin::consume_invitation(con, &payload.invitation_token)?;
+        let p = create_profile(con, profile.clone())?;
+        create_wallet(con, &p.id)?;
+        Ok(p)
+    })?;
+    Ok(Json(UserProfileJson {
+        id: p.id,
+        username: p.username,
+        email: p.email,
+        subscription_level: p.subscription_level,
+    }))
 }
 
 async fn update_user_profile(
+    auth: AuthUser,
     State(state): State<SharedState>,
     Path(user_id): Path<String>,
     Json(payload): Json<UserProfileJson>,
-) -> Result<Json<UserProfileJson>, StatusCode> {
-    let mut con = state.lock().unwrap();
+) -> Result<Json<UserProfileJson>, AppError> {
+    require_self(&auth, &user_id)?;
     let profile = UserProfile {
         id: user_id.clone(),
         username: payload.username,
@@ -261,63 +342,84 @@ async fn update_user_profile(
         subscription_level: payload.subscription_level,
         history_key: format!("{}:history", user_id),
     };
-    match update_profile(&mut *con, profile) {
-        Ok(p) => Ok(Json(UserProfileJson {
-            id: p.id,
-            username: p.username,
-            email: p.email,
-            subscription_level: p.subscription_level,
-        })),
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
-    }
+    let p = with_redis(&state, |con| update_profile(con, profile.clone())).await?;
+    Ok(Json(UserProfileJson {
+        id: p.id,
+        username: p.username,
+        email: p.email,
+        subscription_level: p.subscription_level,
+    }))
 }
 
 async fn get_wallet(
+    auth: AuthUser,
     State(state): State<SharedState>,
     Path(user_id): Path<String>,
-) -> Result<Json<WalletJson>, StatusCode> {
-    let mut con = state.lock().unwrap();
-    match read_wallet(&mut *con, &user_id) {
-        Ok(wallet) => Ok(Json(WalletJson {
-            coin_balance: wallet.coin_balance,
-            credit_balance: wallet.credit_balance,
-        })),
-        Err(_) => Err(StatusCode::NOT_FOUND),
-    }
+) -> Result<Json<WalletJson>, AppError> {
+    require_self(&auth, &user_id)?;
+    let wallet = with_redis(&state, |con| read_wallet(con, &user_id)).await?;
+    Ok(Json(WalletJson {
+        coin_balance: wallet.coin_balance,
+        credit_balance: wallet.credit_balance,
+    }))
+}
+
+async fn get_rate(State(state): State<SharedState>) -> Result<Json<RateJson>, AppError> {
+    let mut source = state.rate.lock().unwrap();
+    let rate = source
+        .latest_rate()
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    Ok(Json(RateJson { ask: rate.ask }))
 }
 
 async fn transfer_credits(
+    auth: AuthUser,
     State(state): State<SharedState>,
     Path(user_id): Path<String>,
     Json(payload): Json<TransferRequest>,
-) -> Result<Json<WalletJson>, StatusCode> {
-    let mut con = state.lock().unwrap();
-    match transfer_credit_transaction(&mut *con, &user_id, payload.amount) {
-        Ok(wallet) => Ok(Json(WalletJson {
-            coin_balance: wallet.coin_balance,
-            credit_balance: wallet.credit_balance,
-        })),
-        Err(e) => {
-            let error_msg = e.to_string();
-            if error_msg.contains("Insufficient coins") || error_msg.contains("must be positive") {
-                Err(StatusCode::BAD_REQUEST)
-            } else {
-                Err(StatusCode::INTERNAL_SERVER_ERROR)
-            }
-        }
-    }
+) -> Result<Json<WalletJson>, AppError> {
+    require_self(&auth, &user_id)?;
+    let rate = {
+        let mut source = state.rate.lock().unwrap();
+        source
+            .latest_rate()
+            .map_err(|e| AppError::Internal(e.to_string()))?
+    };
+    let wallet = with_redis(&state, |con| {
+        transfer_credit_transaction(con, &user_id, payload.amount, rate)
+    })
+    .await?;
+    Ok(Json(WalletJson {
+        coin_balance: wallet.coin_balance,
+        credit_balance: wallet.credit_balance,
+    }))
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     let client = redis::Client::open("redis://127.0.0.1:6379/")?;
-    let con = client.get_connection()?;
-    let shared_state = Arc::new(Mutex::new(con));
+    let redis_pool: RedisPool = r2d2::Pool::builder().max_size(15).build(client)?;
+
+    let rate_feed_url = env::var("RATE_FEED_URL").ok();
+    let rate_source: RateSource = match rate_feed_url {
+        Some(url) => {
+            let current = rate::spawn_live_rate_feed(url);
+            Arc::new(Mutex::new(LiveRate::new(current)))
+        }
+        None => Arc::new(Mutex::new(FixedRate::default())),
+    };
+
+    let shared_state = Arc::new(AppState {
+        redis: redis_pool,
+        rate: rate_source,
+        retry: RetryConfig::default(),
+        auth: AuthConfig::default(),
+    });
 
     println!("Connected to Redis!");
 
     {
-        let mut con = shared_state.lock().unwrap();
+        let mut con = shared_state.redis.get()?;
         let user_id = "user:1234";
 
         let pre_built_genres = vec![
@@ -347,17 +449,34 @@ async fn main() -> Result<(), Box<dyn Error>> {
         println!("Initial data setup complete!");
     }
 
+    // Wildcard methods/headers can't be combined with credentialed
+    // requests (tower-http asserts this and panics on the first
+    // preflight), so the cookie-authenticated routes need an explicit
+    // allow-list here instead of `Any`.
     let cors = CorsLayer::new()
         .allow_origin("http://localhost:3000".parse::<HeaderValue>().unwrap())
-        .allow_methods(Any)
-        .allow_headers(Any);
+        .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
+        .allow_headers([header::CONTENT_TYPE, HeaderName::from_static("x-admin-token")])
+        .allow_credentials(true);
 
     let app = Router::new()
         .route("/genres", get(get_all_genres).post(create_genre_handler))
-        .route("/users/{user_id}", get(get_user_profile).put(update_user_profile))
+        .route(
+            "/users/{user_id}",
+            get(get_user_profile)
+                .put(update_user_profile)
+                .delete(admin::delete_user_handler),
+        )
         .route("/users", post(create_user_profile))
         .route("/users/{user_id}/wallet", get(get_wallet))
         .route("/users/{user_id}/wallet/transfer", post(transfer_credits))
+        .route("/users/{user_id}/plays", post(plays::record_play_handler))
+        .route("/users/{user_id}/history", get(plays::get_history_handler))
+        .route("/genres/top", get(plays::top_genres_handler))
+        .route("/auth/login", post(auth::login_handler))
+        .route("/rate", get(get_rate))
+        .route("/admin/users", get(admin::list_users_handler))
+        .route("/admin/invitations", post(admin::create_invitation_handler))
         .layer(cors)
         .with_state(shared_state);
 
@@ -0,0 +1,167 @@
+use axum::extract::{Path, Query, State};
+use axum::response::Json;
+use prost::Message;
+use redis::Commands;
+use serde::{Deserialize, Serialize};
+
+use crate::auth::AuthUser;
+use crate::error::AppError;
+use crate::protos::redis_demo::Genre;
+use crate::{
+    decode_protobuf, read_genre, read_profile, require_self, with_redis, with_redis_once,
+    SharedState,
+};
+
+const MAX_HISTORY_LEN: isize = 100;
+const GENRES_BY_LISTENERS_KEY: &str = "genres:by_listeners";
+
+#[derive(Deserialize)]
+pub struct RecordPlayRequest {
+    genre_id: String,
+}
+
+#[derive(Serialize)]
+pub struct PlayJson {
+    genre_id: String,
+    listeners: i32,
+}
+
+/// Loads the genre's metadata, bumps its listener count, and writes it
+/// back inside a WATCH/MULTI transaction so a concurrent play for the
+/// same genre can't clobber this increment.
+fn increment_genre_listeners(con: &mut impl Commands, genre_id: &str) -> Result<Genre, AppError> {
+    let key = format!("genre:{}:metadata", genre_id);
+    let mut failure: Option<AppError> = None;
+
+    let transaction_result: redis::RedisResult<Genre> =
+        redis::transaction(con, &[&key], |con, pipe| {
+            let bytes: Vec<u8> = con.get(&key)?;
+            if bytes.is_empty() {
+                failure = Some(AppError::NotFound);
+                return Err((redis::ErrorKind::ExtensionError, "aborted").into());
+            }
+            let mut genre: Genre = match decode_protobuf(bytes) {
+                Ok(g) => g,
+                Err(e) => {
+                    failure = Some(e);
+                    return Err((redis::ErrorKind::ExtensionError, "aborted").into());
+                }
+            };
+
+            genre.listeners += 1;
+            let mut buf = Vec::new();
+            if genre.encode(&mut buf).is_err() {
+                failure = Some(AppError::InvalidInput("failed to encode genre".to_string()));
+                return Err((redis::ErrorKind::ExtensionError, "aborted").into());
+            }
+            pipe.set(&key, buf).ignore().query::<()>(con)?;
+            Ok(Some(genre))
+        });
+
+    match (transaction_result, failure) {
+        (Ok(genre), _) => Ok(genre),
+        (Err(_), Some(app_err)) => Err(app_err),
+        (Err(e), None) => Err(AppError::Redis(e)),
+    }
+}
+
+pub async fn record_play_handler(
+    auth: AuthUser,
+    State(state): State<SharedState>,
+    Path(user_id): Path<String>,
+    Json(payload): Json<RecordPlayRequest>,
+) -> Result<Json<PlayJson>, AppError> {
+    require_self(&auth, &user_id)?;
+
+    let profile = with_redis(&state, |con| read_profile(con, &user_id)).await?;
+    // Validate the genre exists before writing anything, so a play for an
+    // unknown genre doesn't leave an orphan history entry behind a 404.
+    with_redis(&state, |con| read_genre(con, &payload.genre_id)).await?;
+
+    // LPUSH/LTRIM/ZINCRBY aren't idempotent, so this runs once with no
+    // retry: replaying it on a transient error would duplicate the
+    // history entry and double-count the genre's listeners.
+    let genre = with_redis_once(&state, |con| {
+        let _: () = con.lpush(&profile.history_key, &payload.genre_id)?;
+        let _: () = con.ltrim(&profile.history_key, 0, MAX_HISTORY_LEN - 1)?;
+        let genre = increment_genre_listeners(con, &payload.genre_id)?;
+        let _: () = con.zincr(GENRES_BY_LISTENERS_KEY, &payload.genre_id, 1)?;
+        Ok(genre)
+    })?;
+
+    Ok(Json(PlayJson {
+        genre_id: genre.id,
+        listeners: genre.listeners,
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct HistoryQuery {
+    limit: Option<isize>,
+}
+
+#[derive(Serialize)]
+pub struct HistoryJson {
+    genre_ids: Vec<String>,
+}
+
+pub async fn get_history_handler(
+    auth: AuthUser,
+    State(state): State<SharedState>,
+    Path(user_id): Path<String>,
+    Query(query): Query<HistoryQuery>,
+) -> Result<Json<HistoryJson>, AppError> {
+    require_self(&auth, &user_id)?;
+    let limit = query.limit.unwrap_or(20).max(0);
+
+    let genre_ids = with_redis(&state, |con| {
+        let profile = read_profile(con, &user_id)?;
+        // LRANGE's end index is inclusive, so `limit - 1` is only valid
+        // once `limit == 0` is handled separately: `0, -1` would otherwise
+        // return the whole list instead of nothing.
+        if limit == 0 {
+            return Ok(Vec::new());
+        }
+        let ids: Vec<String> = con.lrange(&profile.history_key, 0, limit - 1)?;
+        Ok(ids)
+    })
+    .await?;
+
+    Ok(Json(HistoryJson { genre_ids }))
+}
+
+#[derive(Deserialize)]
+pub struct TopGenresQuery {
+    limit: Option<isize>,
+}
+
+#[derive(Serialize)]
+pub struct TopGenreJson {
+    id: String,
+    listeners: f64,
+}
+
+pub async fn top_genres_handler(
+    State(state): State<SharedState>,
+    Query(query): Query<TopGenresQuery>,
+) -> Result<Json<Vec<TopGenreJson>>, AppError> {
+    let limit = query.limit.unwrap_or(10).max(0);
+
+    let ranked = with_redis(&state, |con| {
+        // Same off-by-one as history: `0, -1` means "everything" to
+        // ZREVRANGE, so `limit == 0` needs its own empty-result case.
+        if limit == 0 {
+            return Ok(Vec::new());
+        }
+        let ranked: Vec<(String, f64)> =
+            con.zrevrange_withscores(GENRES_BY_LISTENERS_KEY, 0, limit - 1)?;
+        Ok(ranked)
+    })
+    .await?;
+
+    let top = ranked
+        .into_iter()
+        .map(|(id, listeners)| TopGenreJson { id, listeners })
+        .collect();
+    Ok(Json(top))
+}
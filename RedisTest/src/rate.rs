@@ -0,0 +1,124 @@
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use futures_util::StreamExt;
+use serde::Deserialize;
+use tokio_tungstenite::tungstenite::Message;
+
+/// The current coin -> credit exchange rate.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct Rate {
+    pub ask: f64,
+}
+
+pub type RateError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Source of the coin -> credit exchange rate used by `transfer_credit_transaction`.
+///
+/// Lets the conversion rate be swapped between a hardcoded default and a
+/// live feed without touching the transfer logic itself.
+pub trait LatestRate {
+    type Error;
+
+    fn latest_rate(&mut self) -> Result<Rate, Self::Error>;
+}
+
+/// Shared handle threaded through axum state so handlers can ask for the
+/// current rate regardless of which `LatestRate` implementation is active.
+pub type RateSource = Arc<Mutex<dyn LatestRate<Error = RateError> + Send>>;
+
+/// Static default rate (0.01 credits per coin), used when no live feed is
+/// configured.
+pub struct FixedRate(pub Rate);
+
+impl Default for FixedRate {
+    fn default() -> Self {
+        FixedRate(Rate { ask: 0.01 })
+    }
+}
+
+impl LatestRate for FixedRate {
+    type Error = RateError;
+
+    fn latest_rate(&mut self) -> Result<Rate, Self::Error> {
+        Ok(self.0)
+    }
+}
+
+/// Rate backed by a background task that keeps `current` fresh from a
+/// websocket feed. Reading the rate never blocks on the network: it just
+/// takes a snapshot of whatever the feeder last saw.
+pub struct LiveRate {
+    current: Arc<RwLock<Rate>>,
+}
+
+impl LiveRate {
+    pub fn new(current: Arc<RwLock<Rate>>) -> Self {
+        LiveRate { current }
+    }
+}
+
+impl LatestRate for LiveRate {
+    type Error = RateError;
+
+    fn latest_rate(&mut self) -> Result<Rate, Self::Error> {
+        let guard = self
+            .current
+            .read()
+            .map_err(|_| "rate lock poisoned".to_string())?;
+        Ok(*guard)
+    }
+}
+
+#[derive(Deserialize)]
+struct RateUpdate {
+    ask: f64,
+}
+
+/// Launches the background feeder task and returns the shared cell it keeps
+/// updated. Intended to be called once from `main` and handed to `LiveRate`.
+///
+/// Disconnects are expected (the feed is a best-effort external service), so
+/// the task keeps the last good rate and reconnects with exponential
+/// backoff instead of tearing down the server.
+pub fn spawn_live_rate_feed(feed_url: String) -> Arc<RwLock<Rate>> {
+    let current = Arc::new(RwLock::new(FixedRate::default().0));
+    let task_current = current.clone();
+
+    tokio::spawn(async move {
+        let min_backoff = Duration::from_secs(1);
+        let max_backoff = Duration::from_secs(30);
+        let mut backoff = min_backoff;
+
+        loop {
+            match run_feed(&feed_url, &task_current).await {
+                Ok(()) => backoff = min_backoff,
+                Err(e) => eprintln!("rate feed error: {e}"),
+            }
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(max_backoff);
+        }
+    });
+
+    current
+}
+
+async fn run_feed(feed_url: &str, current: &Arc<RwLock<Rate>>) -> Result<(), RateError> {
+    let (ws_stream, _) = tokio_tungstenite::connect_async(feed_url).await?;
+    let (_, mut read) = ws_stream.split();
+
+    while let Some(msg) = read.next().await {
+        let Message::Text(text) = msg? else {
+            continue;
+        };
+        let update: RateUpdate = match serde_json::from_str(&text) {
+            Ok(update) => update,
+            Err(_) => continue,
+        };
+        if let Ok(mut guard) = current.write() {
+            guard.ask = update.ask;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,57 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+use crate::error::AppError;
+
+/// Backoff parameters for [`retryable`].
+#[derive(Debug, Clone, Copy)]
+pub struct RetryConfig {
+    pub max_attempts: u32,
+    pub base_delay_ms: u64,
+    pub max_delay_ms: u64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        RetryConfig {
+            max_attempts: 3,
+            base_delay_ms: 50,
+            max_delay_ms: 1000,
+        }
+    }
+}
+
+/// Runs `op`, retrying transient errors (connection reset, broken pipe,
+/// timeouts, pool exhaustion) with exponential backoff and jitter, up to
+/// `config.max_attempts`. Logical errors such as "Insufficient funds" are
+/// never transient, so they're returned on the first attempt.
+///
+/// `op` itself stays synchronous (redis-rs is a blocking client), but the
+/// backoff sleep between attempts uses the tokio timer so a transient
+/// error doesn't park the async worker thread it was called from.
+pub async fn retryable<T>(
+    config: &RetryConfig,
+    mut op: impl FnMut() -> Result<T, AppError>,
+) -> Result<T, AppError> {
+    let mut attempt = 1;
+    loop {
+        match op() {
+            Ok(value) => return Ok(value),
+            Err(e) if attempt < config.max_attempts && e.is_transient() => {
+                tokio::time::sleep(backoff_delay(config, attempt)).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn backoff_delay(config: &RetryConfig, attempt: u32) -> Duration {
+    let exp_ms = config
+        .base_delay_ms
+        .saturating_mul(1u64 << attempt.min(16))
+        .min(config.max_delay_ms);
+    let jitter_ms = rand::thread_rng().gen_range(0..=exp_ms / 2 + 1);
+    Duration::from_millis(exp_ms / 2 + jitter_ms)
+}